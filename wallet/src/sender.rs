@@ -25,11 +25,300 @@ use types::*;
 use util::LOGGER;
 use util;
 
-/// Issue a new transaction to the provided sender by spending some of our
-/// wallet
-/// UTXOs. The destination can be "stdout" (for command line) or a URL to the
-/// recipients wallet receiver (to be implemented).
+/// Maximum number of branch-and-bound search nodes to explore before giving
+/// up and falling back to the existing largest-first accumulation.
+const BNB_MAX_ITERATIONS: usize = 100_000;
+
+/// Outputs below this value are dust: not worth the fee it would take to
+/// ever spend them, so `inputs_and_change` folds such a leftover into the
+/// fee rather than writing it into the wallet as change. A leftover too
+/// small to cover even a changeless tx's fee is the exception: it is still
+/// written out as a (tiny) change output. Kept
+/// small, on the order of a `cost_of_change`, since folding a leftover this
+/// size into the fee (see `inputs_and_change`) is what actually happens to
+/// it - the bigger this is, the more a changeless send silently overpays.
+const DUST_AMOUNT: u64 = 500;
+
+/// Hard cap, in nanogrin, on any fee we'll let a send pay, regardless of
+/// amount.
+const MAX_ABSOLUTE_FEE: u64 = 10_000_000;
+
+/// Hard cap on any fee we'll let a send pay, as a percentage of the amount
+/// being sent.
+const MAX_RELATIVE_FEE_PERCENT: u64 = 3;
+
+/// Below this, the relative cap doesn't apply: a tiny absolute fee (e.g. the
+/// base fee on a small send, or a dust leftover folded in by
+/// `inputs_and_change`) shouldn't be rejected just because it happens to be
+/// a large percentage of a small amount.
+const FEE_RELATIVE_FLOOR: u64 = 1_000;
+
+/// Guards against a miscalculated or maliciously suggested fee silently
+/// burning a large fraction of `amount`. `Error::FeeExceedsLimit` is a new
+/// variant alongside `Error`'s other cases in `types.rs`.
+fn check_fee_limit(fee: u64, amount: u64) -> Result<(), Error> {
+	if fee > MAX_ABSOLUTE_FEE {
+		return Err(Error::FeeExceedsLimit { fee: fee, amount: amount });
+	}
+	if fee > FEE_RELATIVE_FLOOR && fee * 100 > amount * MAX_RELATIVE_FEE_PERCENT {
+		return Err(Error::FeeExceedsLimit { fee: fee, amount: amount });
+	}
+	Ok(())
+}
+
+/// A suggested fee rate for confirming within a target number of blocks, as
+/// reported by the node via `checker::get_fee_rate_estimate` (added
+/// alongside `checker`'s other node-querying helpers). Scales the base
+/// `tx_fee` up or down so callers can trade cost against confirmation speed
+/// instead of always paying the fixed base rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeeRate {
+	/// Target number of blocks the transaction should confirm within.
+	pub target_block: usize,
+	/// Suggested fee multiplier for that target, in percent; 100 means "no
+	/// change from the base fee".
+	pub percent: u64,
+}
+
+impl FeeRate {
+	/// Scales a base fee (as computed by `tx_fee`) according to this rate.
+	fn scale(&self, base_fee: u64) -> u64 {
+		base_fee * self.percent / 100
+	}
+}
+
+/// Computes the fee for `n_inputs`/`n_outputs` via `tx_fee`, then scales it
+/// by `fee_rate` if one was requested. Falls back to the fixed base fee
+/// when `fee_rate` is `None`, matching today's behavior.
+fn scaled_tx_fee(n_inputs: usize, n_outputs: usize, fee_rate: Option<FeeRate>) -> u64 {
+	let base_fee = tx_fee(n_inputs, n_outputs, None);
+	match fee_rate {
+		Some(rate) => rate.scale(base_fee),
+		None => base_fee,
+	}
+}
+
+/// Strategy used to select which outputs to spend when funding a
+/// transaction. `WalletData::select_coins` takes this directly now, in
+/// place of its old `default_strategy: bool` parameter - that signature
+/// change lives alongside the rest of `WalletData` in `types.rs`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionStrategy {
+	/// Accumulate the largest outputs first until the target amount is
+	/// covered. This is today's default behavior.
+	LargestFirst,
+	/// Search for a subset of outputs whose total lands in the window
+	/// `[target, target + cost_of_change]`, so no change output is needed.
+	/// Falls back to `LargestFirst` if no such subset is found.
+	BranchAndBound,
+}
+
+/// Attempts to find a subset of `coins` whose total value falls within
+/// `[target, target + cost_of_change]`. `coins` does not need to be
+/// pre-sorted. Explores an include/exclude decision tree depth-first over
+/// coins sorted by value descending, pruning branches that already exceed
+/// the window or that cannot possibly reach `target` given what's left to
+/// explore. Returns `None` if no match is found within
+/// `BNB_MAX_ITERATIONS` search nodes.
+fn branch_and_bound_selection(
+	coins: &[OutputData],
+	target: u64,
+	cost_of_change: u64,
+) -> Option<Vec<OutputData>> {
+	let mut sorted = coins.to_vec();
+	sorted.sort_by(|a, b| b.value.cmp(&a.value));
+	let upper_bound = target + cost_of_change;
+
+	// remaining[i] holds the sum of sorted[i..], so we can prune a branch
+	// as soon as even taking every remaining coin can't reach the target
+	let mut remaining = vec![0u64; sorted.len() + 1];
+	for i in (0..sorted.len()).rev() {
+		remaining[i] = remaining[i + 1] + sorted[i].value;
+	}
+
+	let mut selected = vec![];
+	let mut best = None;
+	let mut iterations = 0;
+	search(
+		&sorted,
+		&remaining,
+		target,
+		upper_bound,
+		0,
+		0,
+		&mut selected,
+		&mut best,
+		&mut iterations,
+	);
+	best.map(|indices: Vec<usize>| indices.into_iter().map(|i| sorted[i].clone()).collect())
+}
+
+/// Depth-first include/exclude search used by `branch_and_bound_selection`.
+fn search(
+	sorted: &[OutputData],
+	remaining: &[u64],
+	target: u64,
+	upper_bound: u64,
+	index: usize,
+	sum: u64,
+	selected: &mut Vec<usize>,
+	best: &mut Option<Vec<usize>>,
+	iterations: &mut usize,
+) {
+	if best.is_some() || *iterations >= BNB_MAX_ITERATIONS {
+		return;
+	}
+	*iterations += 1;
+
+	if sum >= target && sum <= upper_bound {
+		*best = Some(selected.clone());
+		return;
+	}
+	if sum > upper_bound || index == sorted.len() || sum + remaining[index] < target {
+		return;
+	}
+
+	selected.push(index);
+	search(
+		sorted, remaining, target, upper_bound, index + 1, sum + sorted[index].value, selected,
+		best, iterations,
+	);
+	selected.pop();
+
+	search(
+		sorted, remaining, target, upper_bound, index + 1, sum, selected, best, iterations,
+	);
+}
+
+/// Fluent builder for a send transaction, modeled after the options
+/// `issue_send_tx` grew over time. Defaults mirror `issue_send_tx`'s
+/// previous behavior, so callers only need to set what they care about.
+/// Gives us room to add more options (fee rate, multiple recipients, dust
+/// policy, ...) without breaking every call site each time.
+pub struct SendTxBuilder<'a> {
+	config: &'a WalletConfig,
+	keychain: &'a Keychain,
+	recipients: Vec<(u64, String)>,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	selection_strategy: SelectionStrategy,
+	lock_height: Option<u64>,
+	fee_rate_target: Option<usize>,
+}
+
+impl<'a> SendTxBuilder<'a> {
+	/// Starts a new builder against the given wallet config and keychain.
+	pub fn new(config: &'a WalletConfig, keychain: &'a Keychain) -> SendTxBuilder<'a> {
+		SendTxBuilder {
+			config,
+			keychain,
+			recipients: vec![],
+			minimum_confirmations: 1,
+			max_outputs: 500,
+			selection_strategy: SelectionStrategy::LargestFirst,
+			lock_height: None,
+			fee_rate_target: None,
+		}
+	}
+
+	/// Sets the amount to send to the most recently added recipient. For a
+	/// single-recipient send this is typically chained with `to`; for
+	/// several recipients use `recipient` or `recipients` instead.
+	pub fn amount(mut self, amount: u64) -> SendTxBuilder<'a> {
+		match self.recipients.last_mut() {
+			Some(recipient) => recipient.0 = amount,
+			None => self.recipients.push((amount, String::new())),
+		}
+		self
+	}
+
+	/// Sets the minimum number of confirmations an output must have to be
+	/// considered spendable.
+	pub fn minimum_confirmations(mut self, minimum_confirmations: u64) -> SendTxBuilder<'a> {
+		self.minimum_confirmations = minimum_confirmations;
+		self
+	}
+
+	/// Sets the destination of the most recently added recipient:
+	/// `"stdout"` to print the slate, or an `http://IP:port` URL to post it
+	/// to the recipient's wallet receiver.
+	pub fn to(mut self, dest: String) -> SendTxBuilder<'a> {
+		match self.recipients.last_mut() {
+			Some(recipient) => recipient.1 = dest,
+			None => self.recipients.push((0, dest)),
+		}
+		self
+	}
+
+	/// Adds another `(amount, dest)` recipient, allowing several payees to
+	/// be paid out in a single `finalize()` call, each via their own
+	/// independent partial transaction.
+	pub fn recipient(mut self, amount: u64, dest: String) -> SendTxBuilder<'a> {
+		self.recipients.push((amount, dest));
+		self
+	}
+
+	/// Replaces the full recipient list outright.
+	pub fn recipients(mut self, recipients: Vec<(u64, String)>) -> SendTxBuilder<'a> {
+		self.recipients = recipients;
+		self
+	}
+
+	/// Caps the number of outputs considered during coin selection.
+	pub fn max_outputs(mut self, max_outputs: usize) -> SendTxBuilder<'a> {
+		self.max_outputs = max_outputs;
+		self
+	}
+
+	/// Sets the strategy used to select which outputs to spend.
+	pub fn selection_strategy(mut self, selection_strategy: SelectionStrategy) -> SendTxBuilder<'a> {
+		self.selection_strategy = selection_strategy;
+		self
+	}
+
+	/// Overrides the lock_height placed on the transaction. Defaults to the
+	/// current chain tip height.
+	pub fn lock_height(mut self, lock_height: u64) -> SendTxBuilder<'a> {
+		self.lock_height = Some(lock_height);
+		self
+	}
 
+	/// Requests a fee scaled for confirmation within `target_block` blocks,
+	/// queried from the node at finalize time. Defaults to the fixed base
+	/// fee when never called.
+	pub fn fee_rate(mut self, target_block: usize) -> SendTxBuilder<'a> {
+		self.fee_rate_target = Some(target_block);
+		self
+	}
+
+	/// Builds the transaction, locks the spent coins, and sends or prints
+	/// the resulting partial transaction to each recipient's destination.
+	pub fn finalize(self) -> Result<(), Error> {
+		// `Error::NoRecipients` is a new variant alongside `Error`'s other
+		// cases in `types.rs`.
+		if self.recipients.is_empty()
+			|| self.recipients.iter().any(|&(amount, ref dest)| amount == 0 || dest.is_empty())
+		{
+			return Err(Error::NoRecipients);
+		}
+		send_tx(
+			self.config,
+			self.keychain,
+			self.recipients,
+			self.minimum_confirmations,
+			self.max_outputs,
+			self.selection_strategy,
+			self.lock_height,
+			self.fee_rate_target,
+		)
+	}
+}
+
+/// Issue a new transaction to a single recipient by spending some of our
+/// wallet UTXOs. Kept as a compatible entry point for callers built against
+/// the pre-`SendTxBuilder` signature; new callers should prefer
+/// `SendTxBuilder`. The destination can be "stdout" (for command line) or a
+/// URL to the recipient's wallet receiver (to be implemented).
 pub fn issue_send_tx(
 	config: &WalletConfig,
 	keychain: &Keychain,
@@ -37,70 +326,112 @@ pub fn issue_send_tx(
 	minimum_confirmations: u64,
 	dest: String,
 	max_outputs: usize,
-	selection_strategy: bool,
+	selection_strategy: SelectionStrategy,
+) -> Result<(), Error> {
+	SendTxBuilder::new(config, keychain)
+		.recipient(amount, dest)
+		.minimum_confirmations(minimum_confirmations)
+		.max_outputs(max_outputs)
+		.selection_strategy(selection_strategy)
+		.finalize()
+}
+
+/// Issue a new transaction to each of the provided recipients by spending
+/// some of our wallet UTXOs. Each recipient gets their own independent
+/// partial transaction - inputs, change and fee built by us, with the
+/// recipient's own output and signature added on their end - so funding
+/// several payees never requires them to share a blinding factor. Each
+/// recipient's destination can be "stdout" (for command line) or a URL to
+/// their wallet receiver (to be implemented).
+fn send_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	recipients: Vec<(u64, String)>,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	selection_strategy: SelectionStrategy,
+	lock_height: Option<u64>,
+	fee_rate_target: Option<usize>,
 ) -> Result<(), Error> {
 	checker::refresh_outputs(config, keychain)?;
 
 	let chain_tip = checker::get_tip_from_node(config)?;
 	let current_height = chain_tip.height;
 
-	// proof of concept - set lock_height on the tx
-	let lock_height = chain_tip.height;
-
-	let (tx, blind_sum, coins, change_key) = build_send_tx(
-		config,
-		keychain,
-		amount,
-		current_height,
-		minimum_confirmations,
-		lock_height,
-		max_outputs,
-		selection_strategy,
-	)?;
-
-	let partial_tx = build_partial_tx(amount, blind_sum, tx);
-
-	// Closure to acquire wallet lock and lock the coins being spent
-	// so we avoid accidental double spend attempt.
-	let update_wallet = || WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
-		for coin in coins {
-			wallet_data.lock_output(&coin);
-		}
-	});
-
-	// Closure to acquire wallet lock and delete the change output in case of tx failure.
-	let rollback_wallet = || WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
-		info!(LOGGER, "cleaning up unused change output from wallet");
-		wallet_data.delete_output(&change_key);
-	});
-
-	if dest == "stdout" {
-		let json_tx = serde_json::to_string_pretty(&partial_tx).unwrap();
-		update_wallet()?;
-		println!("{}", json_tx);
-	} else if &dest[..4] == "http" {
-		let url = format!("{}/v1/receive/transaction", &dest);
-		debug!(LOGGER, "Posting partial transaction to {}", url);
-		let res = client::send_partial_tx(&url, &partial_tx);
-		match res {
-			Err(_) => {
-				error!(LOGGER, "Communication with receiver failed. Aborting transaction");
-				rollback_wallet()?;
-				return res;
+	// proof of concept - set lock_height on the tx, unless the caller
+	// overrode it explicitly
+	let lock_height = lock_height.unwrap_or(chain_tip.height);
+
+	// if a target confirmation window was requested, ask the node for a
+	// fee rate to hit it; otherwise fall through to the fixed base fee
+	let fee_rate = match fee_rate_target {
+		Some(target_block) => Some(checker::get_fee_rate_estimate(config, target_block)?),
+		None => None,
+	};
+
+	// fund and dispatch each recipient's partial tx in turn, so coins
+	// locked for one round can't be reselected by the next
+	for (amount, dest) in recipients {
+		let (tx, blind_sum, coins, change_key) = build_send_tx(
+			config,
+			keychain,
+			amount,
+			current_height,
+			minimum_confirmations,
+			lock_height,
+			max_outputs,
+			selection_strategy,
+			fee_rate,
+		)?;
+
+		let partial_tx = build_partial_tx(amount, blind_sum, tx);
+
+		// Closure to acquire wallet lock and lock the coins being spent
+		// so we avoid accidental double spend attempt.
+		let update_wallet = || WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+			for coin in &coins {
+				wallet_data.lock_output(coin);
 			}
-			Ok(_) => {
-				update_wallet()?;
+		});
+
+		// Closure to acquire wallet lock and delete the change output in case of tx failure.
+		let rollback_wallet = || WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
+			if let Some(ref change_key) = change_key {
+				info!(LOGGER, "cleaning up unused change output from wallet");
+				wallet_data.delete_output(change_key);
+			}
+		});
+
+		if dest == "stdout" {
+			let json_tx = serde_json::to_string_pretty(&partial_tx).unwrap();
+			update_wallet()?;
+			println!("{}", json_tx);
+		} else if &dest[..4] == "http" {
+			let url = format!("{}/v1/receive/transaction", dest);
+			debug!(LOGGER, "Posting partial transaction to {}", url);
+			let res = client::send_partial_tx(&url, &partial_tx);
+			match res {
+				Err(_) => {
+					error!(LOGGER, "Communication with receiver failed. Aborting transaction");
+					rollback_wallet()?;
+					return res;
+				}
+				Ok(_) => {
+					update_wallet()?;
+				}
 			}
+		} else {
+			panic!("dest formatted as {} but send -d expected stdout or http://IP:port", dest);
 		}
-	} else {
-		panic!("dest formatted as {} but send -d expected stdout or http://IP:port", dest);
 	}
+
 	Ok(())
 }
 
 /// Builds a transaction to send to someone from the HD seed associated with the
 /// wallet and the amount to send. Handles reading through the wallet data file,
-/// selecting outputs to spend and building the change.
+/// selecting outputs to spend and building the change. The recipient's own
+/// output is added on their end once they receive the partial tx, not here.
 fn build_send_tx(
 	config: &WalletConfig,
 	keychain: &Keychain,
@@ -109,10 +440,15 @@ fn build_send_tx(
 	minimum_confirmations: u64,
 	lock_height: u64,
 	max_outputs: usize,
-	default_strategy: bool,
-) -> Result<(Transaction, BlindingFactor, Vec<OutputData>, Identifier), Error> {
+	selection_strategy: SelectionStrategy,
+	fee_rate: Option<FeeRate>,
+) -> Result<(Transaction, BlindingFactor, Vec<OutputData>, Option<Identifier>), Error> {
 	let key_id = keychain.clone().root_key_id();
 
+	// one output for the recipient, added on their end, plus one more if
+	// we end up needing change
+	let num_outputs = 2;
+
 	// select some spendable coins from the wallet
 	let coins = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
 		wallet_data.select_coins(
@@ -121,12 +457,44 @@ fn build_send_tx(
 			current_height,
 			minimum_confirmations,
 			max_outputs,
-			default_strategy,
+			selection_strategy,
 		)
 	})?;
 
+	// if branch-and-bound is requested, try to narrow the selected coins down
+	// to a subset that lands in the window [target, target + cost_of_change]
+	// so inputs_and_change can skip the change output entirely; fall back to
+	// the coins returned above (largest-first) if no such subset exists
+	let coins = if selection_strategy == SelectionStrategy::BranchAndBound {
+		let fee_no_change = scaled_tx_fee(coins.len(), num_outputs - 1, fee_rate);
+		let cost_of_change = scaled_tx_fee(coins.len(), num_outputs, fee_rate) - fee_no_change;
+		let target = amount + fee_no_change;
+		match branch_and_bound_selection(&coins, target, cost_of_change) {
+			// the window above was sized off the full candidate pool, but
+			// a branch-and-bound match almost always spends fewer inputs
+			// than that, and fewer inputs means a smaller real fee - so
+			// re-check the subset against the window its own fee implies
+			// before trusting it, and fall back otherwise
+			Some(subset) => {
+				let fee_no_change = scaled_tx_fee(subset.len(), num_outputs - 1, fee_rate);
+				let cost_of_change = scaled_tx_fee(subset.len(), num_outputs, fee_rate) - fee_no_change;
+				let target = amount + fee_no_change;
+				let subset_total: u64 = subset.iter().map(|c| c.value).sum();
+				if subset_total >= target && subset_total <= target + cost_of_change {
+					subset
+				} else {
+					coins
+				}
+			}
+			None => coins,
+		}
+	} else {
+		coins
+	};
+
 	// build transaction skeleton with inputs and change
-	let (mut parts, change_key) = inputs_and_change(&coins, config, keychain, amount)?;
+	let (mut parts, change_key, _) =
+		inputs_and_change(&coins, config, keychain, amount, num_outputs, fee_rate)?;
 
 	// This is more proof of concept than anything but here we set lock_height
 	// on tx being sent (based on current chain height via api).
@@ -137,12 +505,27 @@ fn build_send_tx(
 	Ok((tx, blind, coins, change_key))
 }
 
+/// Burns the given amount by spending it to an unspendable output. Kept as a
+/// compatible entry point for callers built against the pre-`fee_rate`
+/// signature; new callers that want a target confirmation window should use
+/// `burn_tx` directly.
 pub fn issue_burn_tx(
 	config: &WalletConfig,
 	keychain: &Keychain,
 	amount: u64,
 	minimum_confirmations: u64,
 	max_outputs: usize,
+) -> Result<(), Error> {
+	burn_tx(config, keychain, amount, minimum_confirmations, max_outputs, None)
+}
+
+pub fn burn_tx(
+	config: &WalletConfig,
+	keychain: &Keychain,
+	amount: u64,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	fee_rate_target: Option<usize>,
 ) -> Result<(), Error> {
 	let keychain = &Keychain::burn_enabled(keychain, &Identifier::zero());
 
@@ -153,6 +536,13 @@ pub fn issue_burn_tx(
 
 	let key_id = keychain.root_key_id();
 
+	// if a target confirmation window was requested, ask the node for a
+	// fee rate to hit it; otherwise fall through to the fixed base fee
+	let fee_rate = match fee_rate_target {
+		Some(target_block) => Some(checker::get_fee_rate_estimate(config, target_block)?),
+		None => None,
+	};
+
 	// select some spendable coins from the wallet
 	let coins = WalletData::read_wallet(&config.data_file_dir, |wallet_data| {
 		wallet_data.select_coins(
@@ -161,17 +551,23 @@ pub fn issue_burn_tx(
 			current_height,
 			minimum_confirmations,
 			max_outputs,
-			false,
+			SelectionStrategy::LargestFirst,
 		)
 	})?;
 
 	debug!(LOGGER, "selected some coins - {}", coins.len());
 
-	let (mut parts, _) = inputs_and_change(&coins, config, keychain, amount)?;
-
-	// add burn output and fees
-	let fee = tx_fee(coins.len(), 2, None);
-	parts.push(build::output(amount - fee, Identifier::zero()));
+	// add burn output, balanced against whatever fee inputs_and_change actually
+	// applied: with a change output the fee comes out of the burned amount,
+	// while a changeless tx has already folded its leftover into the fee, so
+	// the full amount is burned
+	let (mut parts, change_key, fee) =
+		inputs_and_change(&coins, config, keychain, amount, 2, fee_rate)?;
+	let burn_value = match change_key {
+		Some(_) => amount - fee,
+		None => amount,
+	};
+	parts.push(build::output(burn_value, Identifier::zero()));
 
 	// finalize the burn transaction and send
 	let (tx_burn, _) = build::transaction(parts, &keychain)?;
@@ -189,7 +585,9 @@ fn inputs_and_change(
 	config: &WalletConfig,
 	keychain: &Keychain,
 	amount: u64,
-) -> Result<(Vec<Box<build::Append>>, Identifier), Error> {
+	num_outputs: usize,
+	fee_rate: Option<FeeRate>,
+) -> Result<(Vec<Box<build::Append>>, Option<Identifier>, u64), Error> {
 	let mut parts = vec![];
 
 	// calculate the total across all inputs, and how much is left
@@ -198,10 +596,33 @@ fn inputs_and_change(
 		return Err(Error::NotEnoughFunds(total as u64));
 	}
 
+	// build inputs using the appropriate derived key_ids
+	for coin in coins {
+		let key_id = keychain.derive_key_id(coin.n_child)?;
+		parts.push(build::input(coin.value, key_id));
+	}
+
+	// if what's left over after inputs and amount already falls within the
+	// marginal cost of adding a change output, or is plain dust, it's not
+	// worth creating one - fold the leftover into the fee instead and send
+	// a changeless transaction, as long as the leftover is still enough to
+	// cover the base fee a changeless tx would otherwise require; a leftover
+	// smaller than that would underpay the real fee, so fall through to a
+	// real (if tiny) change output instead
+	let fee_no_change = scaled_tx_fee(coins.len(), num_outputs - 1, fee_rate);
+	let cost_of_change = scaled_tx_fee(coins.len(), num_outputs, fee_rate) - fee_no_change;
+	let change = total - amount;
+	if (change <= cost_of_change || change < DUST_AMOUNT) && change >= fee_no_change {
+		check_fee_limit(change, amount)?;
+		parts.push(build::with_fee(change));
+		return Ok((parts, None, change));
+	}
+
 	// sender is responsible for setting the fee on the partial tx
  // recipient should double check the fee calculation and not blindly trust the
  // sender
-	let fee = tx_fee(coins.len(), 2, None);
+	let fee = scaled_tx_fee(coins.len(), num_outputs, fee_rate);
+	check_fee_limit(fee, amount)?;
 	parts.push(build::with_fee(fee));
 
 	// if we are spending 10,000 coins to send 1,000 then our change will be 9,000
@@ -210,12 +631,6 @@ fn inputs_and_change(
  // but our change will still be 9,000
 	let change = total - amount;
 
-	// build inputs using the appropriate derived key_ids
-	for coin in coins {
-		let key_id = keychain.derive_key_id(coin.n_child)?;
-		parts.push(build::input(coin.value, key_id));
-	}
-
 	// track the output representing our change
 	let change_key = WalletData::with_wallet(&config.data_file_dir, |wallet_data| {
 		let root_key_id = keychain.root_key_id();
@@ -238,13 +653,28 @@ fn inputs_and_change(
 
 	parts.push(build::output(change, change_key.clone()));
 
-	Ok((parts, change_key))
+	Ok((parts, Some(change_key), fee))
 }
 
 #[cfg(test)]
 mod test {
+	use super::{branch_and_bound_selection, check_fee_limit};
 	use core::core::build::{input, output, transaction};
 	use keychain::Keychain;
+	use types::{OutputData, OutputStatus};
+
+	fn test_coin(keychain: &Keychain, n_child: u32, value: u64) -> OutputData {
+		OutputData {
+			root_key_id: keychain.root_key_id(),
+			key_id: keychain.derive_key_id(n_child).unwrap(),
+			n_child: n_child,
+			value: value,
+			status: OutputStatus::Unspent,
+			height: 0,
+			lock_height: 0,
+			is_coinbase: false,
+		}
+	}
 
 	#[test]
 	// demonstrate that input.commitment == referenced output.commitment
@@ -258,4 +688,63 @@ mod test {
 
 		assert_eq!(tx1.outputs[0].commitment(), tx2.inputs[0].commitment());
 	}
+
+	#[test]
+	fn check_fee_limit_rejects_above_absolute_cap() {
+		assert!(check_fee_limit(10_000_001, 1_000_000_000).is_err());
+	}
+
+	#[test]
+	fn check_fee_limit_rejects_above_relative_cap() {
+		// 40 is below the relative floor, so bump it comfortably above it
+		// while still being well over 3% of a 10,000 amount
+		assert!(check_fee_limit(2_000, 10_000).is_err());
+	}
+
+	#[test]
+	fn check_fee_limit_allows_small_fee_under_the_relative_floor() {
+		// 80 is over 3% of 1,000, but under FEE_RELATIVE_FLOOR, so it's let through
+		assert!(check_fee_limit(80, 1_000).is_ok());
+	}
+
+	#[test]
+	fn branch_and_bound_selection_finds_a_subset_in_window() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let coins = vec![
+			test_coin(&keychain, 1, 100),
+			test_coin(&keychain, 2, 50),
+			test_coin(&keychain, 3, 30),
+		];
+
+		let result = branch_and_bound_selection(&coins, 80, 5);
+		let subset = result.expect("a 50+30 subset should land in [80, 85]");
+		let total: u64 = subset.iter().map(|c| c.value).sum();
+		assert!(total >= 80 && total <= 85);
+	}
+
+	#[test]
+	fn branch_and_bound_selection_returns_none_when_nothing_fits() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		let coins = vec![test_coin(&keychain, 1, 1_000)];
+
+		// no subset of a single 1,000 coin can land in [10, 11]
+		assert!(branch_and_bound_selection(&coins, 10, 1).is_none());
+	}
+
+	#[test]
+	fn branch_and_bound_selection_gives_up_within_iteration_cap() {
+		let keychain = Keychain::from_random_seed().unwrap();
+		// enough distinct-valued coins that an exhaustive search for a
+		// target no subset actually reaches would otherwise run away; the
+		// search must still terminate (via BNB_MAX_ITERATIONS) and report
+		// no match rather than hang
+		let coins: Vec<OutputData> = (0..30)
+			.map(|i| test_coin(&keychain, i, 1_000 + i as u64))
+			.collect();
+
+		// any 15 of these coins sum to at most 15,330 and any 16 to at least
+		// 16,120, so nothing lands on 15,500, while every subset of 15 or
+		// fewer coins stays under the bound and has to be explored
+		assert!(branch_and_bound_selection(&coins, 15_500, 0).is_none());
+	}
 }